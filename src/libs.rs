@@ -1,6 +1,7 @@
 use std::{
-    io::{Cursor, Read},
-    net::{Ipv4Addr, UdpSocket},
+    collections::HashMap,
+    io::{Cursor, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
 };
 
 use anyhow::{Ok, Result};
@@ -19,25 +20,210 @@ mod macros {
 }
 
 mod consts {
-    pub const DNS_BUF_SIZE: usize = 1024;
+    // The largest a DNS message can be, so the UDP receive buffer never
+    // cuts a datagram short. The TCP transport sizes its buffer from the
+    // message's 2-byte length prefix instead.
+    pub const DNS_BUF_SIZE: usize = 65535;
     pub const HEADER_SIZE: usize = 12;
     pub const QUESTION_DATA_SIZE: usize = 4;
     pub const RECORD_DATA_SIZE: usize = 10;
+
+    // A name should never need to follow more compression pointers than
+    // this to resolve; anything beyond it is a malformed or hostile packet.
+    pub const MAX_NAME_POINTER_JUMPS: u32 = 16;
+
+    // A real resolution never needs anywhere near this many queries, even
+    // counting every nested lookup triggered by glueless NS referrals.
+    // This budget is shared across an entire `lookup`/`lookup_domain`
+    // call (not reset per nested referral), so it bounds total work
+    // rather than just recursion depth.
+    pub const MAX_RESOLUTION_QUERIES: u32 = 20;
+
+    // How long to wait on a single server before giving up on it; an
+    // unresponsive server anywhere down the delegation chain must not be
+    // able to hang the resolver forever.
+    pub const NETWORK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    // a.root-servers.net through m.root-servers.net.
+    pub const ROOT_SERVERS: [std::net::Ipv4Addr; 13] = [
+        std::net::Ipv4Addr::new(198, 41, 0, 4),
+        std::net::Ipv4Addr::new(199, 9, 14, 201),
+        std::net::Ipv4Addr::new(192, 33, 4, 12),
+        std::net::Ipv4Addr::new(199, 7, 91, 13),
+        std::net::Ipv4Addr::new(192, 203, 230, 10),
+        std::net::Ipv4Addr::new(192, 5, 5, 241),
+        std::net::Ipv4Addr::new(192, 112, 36, 4),
+        std::net::Ipv4Addr::new(198, 97, 190, 53),
+        std::net::Ipv4Addr::new(192, 36, 148, 17),
+        std::net::Ipv4Addr::new(192, 58, 128, 30),
+        std::net::Ipv4Addr::new(193, 0, 14, 129),
+        std::net::Ipv4Addr::new(199, 7, 83, 42),
+        std::net::Ipv4Addr::new(202, 12, 27, 33),
+    ];
 }
 
 pub fn lookup_domain(domain_name: &str) -> Result<Ipv4Addr> {
-    let query = build_query(domain_name, RecordType::A)?;
+    extract_a(lookup(domain_name, RecordType::A)?, domain_name)
+}
+
+fn resolve_address(name: &str, budget: &mut u32) -> Result<Ipv4Addr> {
+    extract_a(lookup_inner(name, RecordType::A, budget)?, name)
+}
+
+fn extract_a(answers: Vec<RData>, name: &str) -> Result<Ipv4Addr> {
+    answers
+        .into_iter()
+        .find_map(|data| match data {
+            RData::A(ip) => Some(ip),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("no A record found for {name}"))
+}
+
+/// Iteratively resolves `name`, starting at a root server and following
+/// NS delegations until an authoritative answer for `record_type` comes
+/// back.
+pub fn lookup(name: &str, record_type: RecordType) -> Result<Vec<RData>> {
+    let mut budget = consts::MAX_RESOLUTION_QUERIES;
+    lookup_inner(name, record_type, &mut budget)
+}
 
-    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
-    socket.send_to(&query, ("8.8.8.8", 53)).unwrap();
+/// The `budget` counter is shared across this entire resolution,
+/// including any nested `resolve_address` calls triggered by glueless NS
+/// referrals, so the total number of queries sent is bounded regardless
+/// of how the referrals branch.
+fn lookup_inner(name: &str, record_type: RecordType, budget: &mut u32) -> Result<Vec<RData>> {
+    let mut server = consts::ROOT_SERVERS[0];
+
+    loop {
+        if *budget == 0 {
+            return Err(anyhow::anyhow!(
+                "resolving {name} used up its query budget"
+            ));
+        }
+        *budget -= 1;
+
+        let packet = query_server(name, record_type.clone(), server)?;
+
+        let answers: Vec<RData> = packet
+            .answers
+            .iter()
+            .filter(|record| record.kind == record_type)
+            .map(|record| record.data.clone())
+            .collect();
+        if !answers.is_empty() {
+            return Ok(answers);
+        }
 
-    let mut response = [0; consts::DNS_BUF_SIZE];
-    let (_, _) = socket.recv_from(&mut response).unwrap();
+        let Some(ns_name) = packet.authorities.iter().find_map(|record| match &record.data {
+            RData::Ns(name) => Some(name.clone()),
+            _ => None,
+        }) else {
+            return Err(anyhow::anyhow!(
+                "no authority found while resolving {name}"
+            ));
+        };
 
-    let packet = parse_dns_packet(&response)?;
-    let [octet1, octet2,octet3,octet4,..] = packet.answers[0].data[0..4] else { return Err(anyhow::anyhow!("data is not correct format."));};
+        server = match find_glue(&packet, &ns_name) {
+            Some(ip) => ip,
+            None => resolve_address(&ns_name, budget)?,
+        };
+    }
+}
+
+fn query_server(name: &str, record_type: RecordType, server: Ipv4Addr) -> Result<DnsPacket> {
+    let (query, id) = build_query(name, record_type)?;
 
-    Ok(Ipv4Addr::new(octet1, octet2, octet3, octet4))
+    let packet = check_response(
+        parse_dns_packet(&Transport::Udp.send(&query, server)?)?,
+        server,
+        name,
+        id,
+    )?;
+
+    if !packet.header.parsed_flags().truncated() {
+        return Ok(packet);
+    }
+
+    // The UDP answer didn't fit; redo the same query over TCP, which has
+    // no practical size limit.
+    check_response(
+        parse_dns_packet(&Transport::Tcp.send(&query, server)?)?,
+        server,
+        name,
+        id,
+    )
+}
+
+fn check_response(packet: DnsPacket, server: Ipv4Addr, name: &str, id: u16) -> Result<DnsPacket> {
+    if packet.header.id != id {
+        return Err(anyhow::anyhow!(
+            "response id {} from {server} did not match query id {id}",
+            packet.header.id
+        ));
+    }
+    if !packet.header.parsed_flags().response() {
+        return Err(anyhow::anyhow!("{server} sent a non-response packet"));
+    }
+
+    match packet.header.parsed_flags().rcode()? {
+        Rcode::NoError => Ok(packet),
+        rcode => Err(anyhow::anyhow!(
+            "{server} returned {rcode:?} while resolving {name}"
+        )),
+    }
+}
+
+/// How a query is sent to a server: plain UDP, or TCP when the UDP
+/// answer came back truncated.
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl Transport {
+    fn send(&self, query: &[u8], server: Ipv4Addr) -> Result<Vec<u8>> {
+        match self {
+            Transport::Udp => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+                socket.set_read_timeout(Some(consts::NETWORK_TIMEOUT))?;
+                socket.set_write_timeout(Some(consts::NETWORK_TIMEOUT))?;
+                socket.send_to(query, (server, 53))?;
+
+                let mut response = [0; consts::DNS_BUF_SIZE];
+                let (len, _) = socket.recv_from(&mut response)?;
+                Ok(response[..len].to_vec())
+            }
+            Transport::Tcp => {
+                let mut stream = TcpStream::connect_timeout(
+                    &SocketAddr::from((server, 53)),
+                    consts::NETWORK_TIMEOUT,
+                )?;
+                stream.set_read_timeout(Some(consts::NETWORK_TIMEOUT))?;
+                stream.set_write_timeout(Some(consts::NETWORK_TIMEOUT))?;
+                stream.write_all(&(query.len() as u16).to_be_bytes())?;
+                stream.write_all(query)?;
+
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+
+                let mut response = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut response)?;
+                Ok(response)
+            }
+        }
+    }
+}
+
+fn find_glue(packet: &DnsPacket, ns_name: &str) -> Option<Ipv4Addr> {
+    packet
+        .additional
+        .iter()
+        .filter(|record| record.name == ns_name)
+        .find_map(|record| match record.data {
+            RData::A(ip) => Some(ip),
+            _ => None,
+        })
 }
 
 trait ToBytes {
@@ -83,35 +269,71 @@ impl TryFrom<&[u8]> for DnsHeader {
     }
 }
 
-fn parse_header<const SIZE: usize>(reader: &mut Cursor<&[u8; SIZE]>) -> Result<DnsHeader> {
+fn parse_header(reader: &mut Cursor<&[u8]>) -> Result<DnsHeader> {
     let header = &mut [0; consts::HEADER_SIZE];
     reader.read_exact(header)?;
     let header: &[u8] = header;
     DnsHeader::try_from(header)
 }
 
+impl DnsHeader {
+    fn parsed_flags(&self) -> Flags {
+        Flags(self.flags)
+    }
+}
+
+/// A read-only view over the bits packed into `DnsHeader.flags`.
+#[derive(Debug, Clone, Copy)]
+struct Flags(u16);
+
+impl Flags {
+    fn response(&self) -> bool {
+        self.0 & 0b1000_0000_0000_0000 != 0
+    }
+
+    fn truncated(&self) -> bool {
+        self.0 & 0b0000_0010_0000_0000 != 0
+    }
+
+    fn rcode(&self) -> Result<Rcode> {
+        Ok(Rcode::try_from(self.0 & 0b0000_0000_0000_1111)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive)]
+#[repr(u16)]
+enum Rcode {
+    NoError = 0,
+    FormErr = 1,
+    ServFail = 2,
+    NXDomain = 3,
+    NotImp = 4,
+    Refused = 5,
+}
+
 #[derive(Debug)]
 struct DnsQuestion {
-    pub name: Vec<u8>,
+    pub name: String,
     pub kind: RecordType,
     pub class: Class,
 }
 
-impl ToBytes for DnsQuestion {
-    fn to_bytes(&self) -> Vec<u8> {
-        [
-            self.name.clone(),
-            (self.kind.clone() as u16).to_be_bytes().to_vec(),
-            (self.class.clone() as u16).to_be_bytes().to_vec(),
-        ]
-        .concat()
+impl DnsQuestion {
+    /// Serializes this question as it would sit at `offset` bytes into a
+    /// packet, compressing its name against any suffix already recorded
+    /// in `names`.
+    fn to_bytes(&self, offset: usize, names: &mut HashMap<String, u16>) -> Vec<u8> {
+        let mut bytes = encode_name_compressed(&self.name, offset, names);
+        bytes.extend_from_slice(&(self.kind.clone() as u16).to_be_bytes());
+        bytes.extend_from_slice(&(self.class.clone() as u16).to_be_bytes());
+        bytes
     }
 }
 
-impl TryFrom<(Vec<u8>, &[u8])> for DnsQuestion {
+impl TryFrom<(String, &[u8])> for DnsQuestion {
     type Error = anyhow::Error;
 
-    fn try_from((name, data): (Vec<u8>, &[u8])) -> std::result::Result<Self, Self::Error> {
+    fn try_from((name, data): (String, &[u8])) -> std::result::Result<Self, Self::Error> {
         Ok(DnsQuestion {
             name,
             kind: RecordType::try_from(extract_bytes!(data, 0..2, u16))?,
@@ -120,30 +342,38 @@ impl TryFrom<(Vec<u8>, &[u8])> for DnsQuestion {
     }
 }
 
-fn parse_question<const SIZE: usize>(reader: &mut Cursor<&[u8; SIZE]>) -> Result<DnsQuestion> {
+fn parse_question(reader: &mut Cursor<&[u8]>) -> Result<DnsQuestion> {
     let name = decord_name(reader)?;
     let data = &mut [0; consts::QUESTION_DATA_SIZE];
     reader.read_exact(data)?;
     let data: &[u8] = data;
-    DnsQuestion::try_from((name.into(), data))
+    DnsQuestion::try_from((name, data))
 }
 
-fn decord_name<const SIZE: usize>(reader: &mut Cursor<&[u8; SIZE]>) -> Result<String> {
+fn decord_name(reader: &mut Cursor<&[u8]>) -> Result<String> {
+    decord_name_inner(reader, 0)
+}
+
+fn decord_name_inner(reader: &mut Cursor<&[u8]>, jumps: u32) -> Result<String> {
     let mut cursor = reader.position();
     let mut parts = Vec::new();
-    let mut length = reader.get_ref()[cursor as usize];
+    let mut length = read_byte(reader, cursor)?;
 
     while length != 0 {
         if (length & 0b1100_0000) != 0 {
-            parts.push(decord_compressed_name(reader)?);
+            // The pointer bytes sit right after any plain labels already
+            // consumed above, not wherever `reader` happened to be when
+            // this call started; seek there before reading them.
+            reader.set_position(cursor);
+            parts.push(decord_compressed_name(reader, jumps)?);
             cursor += 2;
             reader.set_position(cursor);
             return Ok(parts.join("."));
         } else {
             let (start, end) = ((cursor + 1) as usize, (cursor + length as u64 + 1) as usize);
-            parts.push(String::from_utf8(reader.get_ref()[start..end].to_vec())?);
+            parts.push(String::from_utf8(read_range(reader, start, end)?)?);
             cursor += length as u64 + 1;
-            length = reader.get_ref()[cursor as usize];
+            length = read_byte(reader, cursor)?;
         }
     }
 
@@ -152,111 +382,301 @@ fn decord_name<const SIZE: usize>(reader: &mut Cursor<&[u8; SIZE]>) -> Result<St
     Ok(parts.join("."))
 }
 
-fn decord_compressed_name<const SIZE: usize>(reader: &mut Cursor<&[u8; SIZE]>) -> Result<String> {
-    let curr_pos = reader.position() as usize;
-    let curr = reader.get_ref()[curr_pos] & 0b0011_1111;
-    let next = reader.get_ref()[curr_pos + 1];
+fn decord_compressed_name(reader: &mut Cursor<&[u8]>, jumps: u32) -> Result<String> {
+    if jumps >= consts::MAX_NAME_POINTER_JUMPS {
+        return Err(anyhow::anyhow!(
+            "name decoding followed too many compression pointers"
+        ));
+    }
+
+    let curr_pos = reader.position();
+    let curr = read_byte(reader, curr_pos)? & 0b0011_1111;
+    let next = read_byte(reader, curr_pos + 1)?;
     let cursor = u16::from_be_bytes([curr, next]);
     reader.set_position(cursor as u64);
-    decord_name(reader)
+    decord_name_inner(reader, jumps + 1)
 }
 
-fn build_query(domain_name: &str, record_type: RecordType) -> Result<Vec<u8>> {
-    let name = encode_dns_name(domain_name)?;
+fn read_byte(reader: &Cursor<&[u8]>, idx: u64) -> Result<u8> {
+    reader
+        .get_ref()
+        .get(idx as usize)
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("name decoding read past the end of the packet"))
+}
+
+fn read_range(reader: &Cursor<&[u8]>, start: usize, end: usize) -> Result<Vec<u8>> {
+    reader
+        .get_ref()
+        .get(start..end)
+        .map(|slice| slice.to_vec())
+        .ok_or_else(|| anyhow::anyhow!("name decoding read past the end of the packet"))
+}
+
+/// Builds a query packet and returns it alongside its transaction id, so
+/// the caller can check the id on the matching response.
+fn build_query(domain_name: &str, record_type: RecordType) -> Result<(Vec<u8>, u16)> {
     let id = {
         let mut rng = rand::thread_rng();
         rng.gen_range(0..=65535)
     };
     let recursion_desired = 1 << 8;
-    let header = DnsHeader {
-        id,
-        flags: recursion_desired,
-        num_questions: 1,
-        ..Default::default()
-    };
-    let question = DnsQuestion {
-        name,
-        kind: record_type,
-        class: Class::In,
+    let packet = DnsPacket {
+        header: DnsHeader {
+            id,
+            flags: recursion_desired,
+            num_questions: 1,
+            ..Default::default()
+        },
+        questions: vec![DnsQuestion {
+            name: domain_name.to_string(),
+            kind: record_type,
+            class: Class::In,
+        }],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additional: Vec::new(),
     };
 
-    let mut bytes = header_to_bytes(header);
-    bytes.extend_from_slice(&question_to_bytes(question));
-
-    Ok(bytes)
+    Ok((packet.to_bytes(), id))
 }
 
-fn header_to_bytes(header: DnsHeader) -> Vec<u8> {
-    header.to_bytes()
-}
+/// Encodes `name` as DNS wire-format labels, compressing any suffix that
+/// was already written at an earlier offset in the packet into a 2-byte
+/// pointer. This is the inverse of `decord_compressed_name`.
+fn encode_name_compressed(name: &str, offset: usize, names: &mut HashMap<String, u16>) -> Vec<u8> {
+    if name.is_empty() {
+        return vec![0];
+    }
 
-fn question_to_bytes(question: DnsQuestion) -> Vec<u8> {
-    question.to_bytes()
-}
+    if let Some(&pointer) = names.get(name) {
+        return (0b1100_0000_0000_0000 | pointer).to_be_bytes().to_vec();
+    }
 
-fn encode_dns_name(name: &str) -> Result<Vec<u8>> {
-    let mut bytes = Vec::new();
-    for label in name.split('.') {
-        bytes.push(label.len() as u8);
-        bytes.extend_from_slice(label.as_bytes());
+    // Pointers only have 14 bits of offset, so suffixes past that point
+    // can't be referenced and aren't worth recording.
+    if offset <= 0x3FFF {
+        names.insert(name.to_string(), offset as u16);
     }
-    bytes.push(0);
-    Ok(bytes)
+
+    let (label, rest) = name.split_once('.').unwrap_or((name, ""));
+    let mut bytes = vec![label.len() as u8];
+    bytes.extend_from_slice(label.as_bytes());
+    bytes.extend(encode_name_compressed(rest, offset + bytes.len(), names));
+    bytes
 }
 
-#[derive(Debug, Default, Clone, TryFromPrimitive)]
+#[derive(Debug, Default, Clone, PartialEq, TryFromPrimitive)]
 #[repr(u16)]
 pub enum RecordType {
     #[default]
     A = 1,
+    Ns = 2,
+    Cname = 5,
+    Soa = 6,
+    Mx = 15,
+    Txt = 16,
+    Aaaa = 28,
 }
 
-#[derive(Debug, Default, Clone, TryFromPrimitive)]
+#[derive(Debug, Default, Clone, PartialEq, TryFromPrimitive)]
 #[repr(u16)]
 pub enum Class {
     #[default]
     In = 1,
 }
 
+/// The parsed, typed contents of a record's RDATA.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(String),
+    Cname(String),
+    Mx { preference: u16, exchange: String },
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Txt(Vec<String>),
+}
+
+impl RData {
+    /// Serializes this RDATA as it would sit at `offset` bytes into a
+    /// packet (i.e. right after the record's RDLENGTH field), compressing
+    /// any embedded name against suffixes already recorded in `names`.
+    /// This is the inverse of `parse_rdata`.
+    fn to_bytes(&self, offset: usize, names: &mut HashMap<String, u16>) -> Vec<u8> {
+        match self {
+            RData::A(ip) => ip.octets().to_vec(),
+            RData::Aaaa(ip) => ip.octets().to_vec(),
+            RData::Ns(name) => encode_name_compressed(name, offset, names),
+            RData::Cname(name) => encode_name_compressed(name, offset, names),
+            RData::Mx {
+                preference,
+                exchange,
+            } => {
+                let mut bytes = preference.to_be_bytes().to_vec();
+                bytes.extend(encode_name_compressed(exchange, offset + bytes.len(), names));
+                bytes
+            }
+            RData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                let mut bytes = encode_name_compressed(mname, offset, names);
+                bytes.extend(encode_name_compressed(rname, offset + bytes.len(), names));
+                bytes.extend_from_slice(&serial.to_be_bytes());
+                bytes.extend_from_slice(&refresh.to_be_bytes());
+                bytes.extend_from_slice(&retry.to_be_bytes());
+                bytes.extend_from_slice(&expire.to_be_bytes());
+                bytes.extend_from_slice(&minimum.to_be_bytes());
+                bytes
+            }
+            RData::Txt(strings) => {
+                let mut bytes = Vec::new();
+                for s in strings {
+                    bytes.push(s.len() as u8);
+                    bytes.extend_from_slice(s.as_bytes());
+                }
+                bytes
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct DnsRecord {
-    name: Vec<u8>,
+    name: String,
     kind: RecordType,
     class: Class,
     ttl: u32,
-    data: Vec<u8>,
+    data: RData,
+}
+
+impl DnsRecord {
+    /// Serializes this record as it would sit at `offset` bytes into a
+    /// packet, compressing its own name and any name embedded in its
+    /// RDATA against suffixes already recorded in `names`.
+    fn to_bytes(&self, offset: usize, names: &mut HashMap<String, u16>) -> Vec<u8> {
+        let mut bytes = encode_name_compressed(&self.name, offset, names);
+        bytes.extend_from_slice(&(self.kind.clone() as u16).to_be_bytes());
+        bytes.extend_from_slice(&(self.class.clone() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.ttl.to_be_bytes());
+
+        // The RDLENGTH field itself sits between here and the RDATA.
+        let rdata = self.data.to_bytes(offset + bytes.len() + 2, names);
+        bytes.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+
+        bytes
+    }
 }
 
-impl<const SIZE: usize> TryFrom<(Vec<u8>, &mut Cursor<&[u8; SIZE]>)> for DnsRecord {
+impl TryFrom<(String, &mut Cursor<&[u8]>)> for DnsRecord {
     type Error = anyhow::Error;
 
     fn try_from(
-        (name, reader): (Vec<u8>, &mut Cursor<&[u8; SIZE]>),
+        (name, reader): (String, &mut Cursor<&[u8]>),
     ) -> std::result::Result<Self, Self::Error> {
-        let data = &mut [0; consts::RECORD_DATA_SIZE];
-        reader.read_exact(data)?;
+        let header = &mut [0; consts::RECORD_DATA_SIZE];
+        reader.read_exact(header)?;
 
-        let kind = extract_bytes!(data, 0..2, u16);
-        let class = extract_bytes!(data, 2..4, u16);
-        let ttl = extract_bytes!(data, 4..8, u32);
-        let data_len = extract_bytes!(data, 8..10, u16);
+        let kind = RecordType::try_from(extract_bytes!(header, 0..2, u16))?;
+        let class = Class::try_from(extract_bytes!(header, 2..4, u16))?;
+        let ttl = extract_bytes!(header, 4..8, u32);
+        let data_len = extract_bytes!(header, 8..10, u16);
 
-        let mut data = vec![0; data_len as usize];
-        let _ = reader.read_exact(&mut data);
+        let rdata_start = reader.position();
+        let data = parse_rdata(reader, &kind, data_len)?;
+        reader.set_position(rdata_start + data_len as u64);
 
         Ok(DnsRecord {
             name,
-            kind: RecordType::try_from(kind)?,
-            class: Class::try_from(class)?,
+            kind,
+            class,
             ttl,
             data,
         })
     }
 }
 
-fn parse_record<const SIZE: usize>(reader: &mut Cursor<&[u8; SIZE]>) -> Result<DnsRecord> {
+/// Parses a record's RDATA according to its `kind`.
+///
+/// NS/CNAME/SOA/MX names may use compression pointers back into the
+/// packet, so those go through `decord_name` against the shared cursor
+/// rather than being read out of an isolated byte slice.
+fn parse_rdata(reader: &mut Cursor<&[u8]>, kind: &RecordType, data_len: u16) -> Result<RData> {
+    match kind {
+        RecordType::A => {
+            let mut octets = [0; 4];
+            reader.read_exact(&mut octets)?;
+            Ok(RData::A(Ipv4Addr::from(octets)))
+        }
+        RecordType::Aaaa => {
+            let mut octets = [0; 16];
+            reader.read_exact(&mut octets)?;
+            Ok(RData::Aaaa(Ipv6Addr::from(octets)))
+        }
+        RecordType::Ns => Ok(RData::Ns(decord_name(reader)?)),
+        RecordType::Cname => Ok(RData::Cname(decord_name(reader)?)),
+        RecordType::Mx => {
+            let mut preference = [0; 2];
+            reader.read_exact(&mut preference)?;
+            let exchange = decord_name(reader)?;
+            Ok(RData::Mx {
+                preference: u16::from_be_bytes(preference),
+                exchange,
+            })
+        }
+        RecordType::Soa => {
+            let mname = decord_name(reader)?;
+            let rname = decord_name(reader)?;
+            let rest = &mut [0; 20];
+            reader.read_exact(rest)?;
+            Ok(RData::Soa {
+                mname,
+                rname,
+                serial: extract_bytes!(rest, 0..4, u32),
+                refresh: extract_bytes!(rest, 4..8, u32),
+                retry: extract_bytes!(rest, 8..12, u32),
+                expire: extract_bytes!(rest, 12..16, u32),
+                minimum: extract_bytes!(rest, 16..20, u32),
+            })
+        }
+        RecordType::Txt => {
+            let mut data = vec![0; data_len as usize];
+            reader.read_exact(&mut data)?;
+
+            let mut strings = Vec::new();
+            let mut idx = 0;
+            while idx < data.len() {
+                let length = data[idx] as usize;
+                idx += 1;
+                let chunk = data
+                    .get(idx..idx + length)
+                    .ok_or_else(|| anyhow::anyhow!("TXT record data is truncated"))?;
+                strings.push(String::from_utf8(chunk.to_vec())?);
+                idx += length;
+            }
+            Ok(RData::Txt(strings))
+        }
+    }
+}
+
+fn parse_record(reader: &mut Cursor<&[u8]>) -> Result<DnsRecord> {
     let name = decord_name(reader)?;
-    DnsRecord::try_from((name.into(), reader))
+    DnsRecord::try_from((name, reader))
 }
 
 #[derive(Debug)]
@@ -268,7 +688,7 @@ struct DnsPacket {
     pub additional: Vec<DnsRecord>,
 }
 
-fn parse_dns_packet<const SIZE: usize>(data: &[u8; SIZE]) -> Result<DnsPacket> {
+fn parse_dns_packet(data: &[u8]) -> Result<DnsPacket> {
     let mut reader = Cursor::new(data);
 
     let header = parse_header(&mut reader)?;
@@ -294,16 +714,56 @@ fn parse_dns_packet<const SIZE: usize>(data: &[u8; SIZE]) -> Result<DnsPacket> {
     })
 }
 
+impl DnsPacket {
+    /// Serializes the whole packet, sharing one name-compression table
+    /// across the question(s) and all three record sections. This is the
+    /// missing half of `parse_dns_packet`: together they let this crate
+    /// act as a DNS server as well as a resolver.
+    fn to_bytes(&self) -> Vec<u8> {
+        let header = DnsHeader {
+            id: self.header.id,
+            flags: self.header.flags,
+            num_questions: self.questions.len() as u16,
+            num_answers: self.answers.len() as u16,
+            num_authorities: self.authorities.len() as u16,
+            num_additional: self.additional.len() as u16,
+        };
+
+        let mut bytes = header.to_bytes();
+        let mut names = HashMap::new();
+
+        for question in &self.questions {
+            let offset = bytes.len();
+            bytes.extend(question.to_bytes(offset, &mut names));
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additional)
+        {
+            let offset = bytes.len();
+            bytes.extend(record.to_bytes(offset, &mut names));
+        }
+
+        bytes
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::{
+        collections::HashMap,
         io::Cursor,
         net::{Ipv4Addr, UdpSocket},
     };
 
     use crate::libs::parse_dns_packet;
 
-    use super::{build_query, consts, encode_dns_name, header_to_bytes, DnsHeader, RecordType};
+    use super::{
+        build_query, consts, encode_name_compressed, Class, DnsHeader, DnsPacket, DnsQuestion,
+        DnsRecord, RData, RecordType, ToBytes,
+    };
 
     #[test]
     fn test_header() {
@@ -315,26 +775,134 @@ mod test {
             num_authorities: 0,
             num_additional: 0,
         };
-        let h = header_to_bytes(h);
+        let h = h.to_bytes();
         println!("DNS Header -> {:02x?}", h)
     }
 
+    #[test]
+    fn test_dns_packet_round_trip() {
+        let flags = 0b1000_0000_0000_0000;
+        let packet = DnsPacket {
+            header: DnsHeader {
+                id: 0xbeef,
+                flags,
+                num_questions: 1,
+                num_answers: 1,
+                num_authorities: 0,
+                num_additional: 0,
+            },
+            questions: vec![DnsQuestion {
+                name: "example.com".to_string(),
+                kind: RecordType::A,
+                class: Class::In,
+            }],
+            answers: vec![DnsRecord {
+                name: "example.com".to_string(),
+                kind: RecordType::A,
+                class: Class::In,
+                ttl: 300,
+                data: RData::A(Ipv4Addr::new(93, 184, 216, 34)),
+            }],
+            authorities: vec![],
+            additional: vec![],
+        };
+
+        let parsed = parse_dns_packet(&packet.to_bytes()).unwrap();
+
+        assert_eq!(parsed.header.id, 0xbeef);
+        assert!(parsed.header.parsed_flags().response());
+
+        assert_eq!(parsed.questions.len(), 1);
+        assert_eq!(parsed.questions[0].name, "example.com");
+        assert_eq!(parsed.questions[0].kind, RecordType::A);
+        assert_eq!(parsed.questions[0].class, Class::In);
+
+        assert_eq!(parsed.answers.len(), 1);
+        assert_eq!(parsed.answers[0].name, "example.com");
+        assert_eq!(parsed.answers[0].ttl, 300);
+        assert_eq!(
+            parsed.answers[0].data,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34))
+        );
+    }
+
+    #[test]
+    fn test_dns_packet_round_trip_label_then_pointer_name() {
+        // The NS rdata name shares its "example.com" suffix with the
+        // question name, so it encodes as the plain label "ns1" followed
+        // by a pointer back to that earlier suffix -- the compression
+        // shape virtually every real NS referral uses.
+        let packet = DnsPacket {
+            header: DnsHeader {
+                id: 0xbeef,
+                flags: 0b1000_0000_0000_0000,
+                num_questions: 1,
+                num_answers: 0,
+                num_authorities: 1,
+                num_additional: 1,
+            },
+            questions: vec![DnsQuestion {
+                name: "example.com".to_string(),
+                kind: RecordType::Ns,
+                class: Class::In,
+            }],
+            answers: vec![],
+            authorities: vec![DnsRecord {
+                name: "example.com".to_string(),
+                kind: RecordType::Ns,
+                class: Class::In,
+                ttl: 300,
+                data: RData::Ns("ns1.example.com".to_string()),
+            }],
+            additional: vec![DnsRecord {
+                name: "ns1.example.com".to_string(),
+                kind: RecordType::A,
+                class: Class::In,
+                ttl: 300,
+                data: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            }],
+        };
+
+        let parsed = parse_dns_packet(&packet.to_bytes()).unwrap();
+
+        assert_eq!(parsed.authorities.len(), 1);
+        assert_eq!(
+            parsed.authorities[0].data,
+            RData::Ns("ns1.example.com".to_string())
+        );
+        assert_eq!(parsed.additional.len(), 1);
+        assert_eq!(parsed.additional[0].name, "ns1.example.com");
+    }
+
     #[test]
     fn test_encode_dns_name() {
-        let e = encode_dns_name("google.com").unwrap();
+        let e = encode_name_compressed("google.com", 0, &mut HashMap::new());
         assert_eq!(e[0], 6);
         assert_eq!(e[7], 3);
     }
 
+    #[test]
+    fn test_encode_name_compressed_reuses_earlier_suffix() {
+        let mut names = HashMap::new();
+        let first = encode_name_compressed("ns1.example.com", 12, &mut names);
+        let second = encode_name_compressed("ns2.example.com", 12 + first.len(), &mut names);
+
+        // "example.com" was already recorded while encoding the first
+        // name, so the second name's shared suffix collapses into a
+        // 2-byte pointer instead of being spelled out again.
+        assert_eq!(second.len(), 1 + "ns2".len() + 2);
+        assert_eq!(second[second.len() - 2] & 0b1100_0000, 0b1100_0000);
+    }
+
     #[test]
     fn test_build_query() {
-        let q = build_query("www.example.com", RecordType::A).unwrap();
+        let (q, _id) = build_query("www.example.com", RecordType::A).unwrap();
         println!("Build Query -> {:02x?}", q)
     }
 
     #[test]
     fn send_udp_request_to_google_dns_resolver() {
-        let query = build_query("www.example.com", RecordType::A).unwrap();
+        let (query, _id) = build_query("www.example.com", RecordType::A).unwrap();
         let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
         socket.send_to(&query, ("8.8.8.8", 53)).unwrap();
 
@@ -347,11 +915,7 @@ mod test {
         println!("Parsed response -> {:?}", r);
 
         let data = &r.unwrap().answers[0].data;
-        assert_eq!(data.len(), 4);
-        assert_eq!(data[0], 93);
-        assert_eq!(data[1], 184);
-        assert_eq!(data[2], 216);
-        assert_eq!(data[3], 34);
+        assert_eq!(*data, RData::A(Ipv4Addr::new(93, 184, 216, 34)));
     }
 
     #[test]
@@ -375,9 +939,45 @@ mod test {
         buf[15] = 'm' as u8;
         buf[16] = 0;
 
-        let mut cur = Cursor::new(&buf);
+        let mut cur = Cursor::new(&buf[..]);
         let name = super::decord_name(&mut cur);
         assert_eq!(name.unwrap(), "www.example.com");
         assert_eq!(cur.position(), 17);
     }
+
+    #[test]
+    fn test_decode_name_rejects_self_referencing_pointer() {
+        let mut buf = [0; consts::DNS_BUF_SIZE];
+        // A compression pointer at offset 0 that points right back at
+        // itself, which would recurse forever without a jump limit.
+        buf[0] = 0b1100_0000;
+        buf[1] = 0;
+
+        let mut cur = Cursor::new(&buf[..]);
+        let name = super::decord_name(&mut cur);
+        assert!(name.is_err());
+    }
+
+    #[test]
+    fn test_decode_name_rejects_out_of_range_length() {
+        let mut buf = [0; consts::DNS_BUF_SIZE];
+        buf[consts::DNS_BUF_SIZE - 1] = 10;
+
+        let mut cur = Cursor::new(&buf[..]);
+        cur.set_position((consts::DNS_BUF_SIZE - 1) as u64);
+        let name = super::decord_name(&mut cur);
+        assert!(name.is_err());
+    }
+
+    #[test]
+    fn test_parse_rdata_rejects_txt_length_byte_past_end_of_data() {
+        let mut buf = [0; consts::DNS_BUF_SIZE];
+        // A TXT string length of 200 with no bytes following it, which
+        // would slice out of bounds without a bounds check.
+        buf[0] = 200;
+
+        let mut cur = Cursor::new(&buf[..]);
+        let rdata = super::parse_rdata(&mut cur, &RecordType::Txt, 1);
+        assert!(rdata.is_err());
+    }
 }